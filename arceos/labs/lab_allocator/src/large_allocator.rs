@@ -0,0 +1,97 @@
+//! Pluggable backend for allocations too big for the largest slab tier, and
+//! for refilling slabs with fresh backing chunks.
+//!
+//! Buddy allocators round each request up to a power of two, which can
+//! waste up to ~50% on odd large sizes but frees in `O(log n)`. A
+//! first-fit/linked-list allocator wastes almost nothing but frees in
+//! `O(n)`. [`Heap`](crate::Heap) is generic over this trait so callers can
+//! pick whichever tradeoff suits their workload.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+pub trait LargeAllocator {
+    /// Creates a backend already initialized over `[start, start + size)`.
+    fn new(start: usize, size: usize) -> Self;
+
+    /// Extends the backend with more memory, growing its total capacity.
+    ///
+    /// This is a one-shot capacity extension (used by [`Heap::add_memory`]),
+    /// not the inverse of [`alloc`](Self::alloc) — backends are free to
+    /// implement it in ways that only make sense for memory that has never
+    /// been handed out (e.g. `linked_list_allocator::Heap::extend` requires
+    /// `[start, end)` to be contiguous with the existing region, and the
+    /// buddy backend never decrements `used_bytes()` for it). Memory
+    /// previously returned by `alloc` must be freed with
+    /// [`dealloc`](Self::dealloc) instead.
+    ///
+    /// # Safety
+    /// `[start, end)` must be valid, unused memory.
+    unsafe fn add_to_heap(&mut self, start: usize, end: usize);
+
+    fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, ()>;
+
+    /// # Safety
+    /// `ptr` must have been returned by a prior `alloc(layout)` call on this
+    /// backend and not yet freed.
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout);
+
+    fn total_bytes(&self) -> usize;
+    fn used_bytes(&self) -> usize;
+}
+
+impl LargeAllocator for buddy_system_allocator::Heap<32> {
+    fn new(start: usize, size: usize) -> Self {
+        let mut heap = buddy_system_allocator::Heap::<32>::new();
+        unsafe { heap.init(start, size) };
+        heap
+    }
+
+    unsafe fn add_to_heap(&mut self, start: usize, end: usize) {
+        buddy_system_allocator::Heap::add_to_heap(self, start, end)
+    }
+
+    fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, ()> {
+        buddy_system_allocator::Heap::alloc(self, layout).map_err(|_| ())
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        buddy_system_allocator::Heap::dealloc(self, ptr, layout)
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.stats_total_bytes()
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.stats_alloc_actual()
+    }
+}
+
+impl LargeAllocator for linked_list_allocator::Heap {
+    fn new(start: usize, size: usize) -> Self {
+        unsafe { linked_list_allocator::Heap::new(start as *mut u8, size) }
+    }
+
+    unsafe fn add_to_heap(&mut self, start: usize, end: usize) {
+        // The linked-list allocator only supports extending its existing
+        // region in place, so `[start, end)` must be contiguous with it.
+        self.extend(end - start)
+    }
+
+    fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, ()> {
+        self.allocate_first_fit(layout).map_err(|_| ())
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        linked_list_allocator::Heap::deallocate(self, ptr, layout)
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.size()
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.used()
+    }
+}
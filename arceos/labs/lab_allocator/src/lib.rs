@@ -1,13 +1,22 @@
 //! Allocator algorithm in lab.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![allow(unused_variables)]
 #![feature(allocator_api)]
 
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+mod large_allocator;
+mod locked_heap;
 mod slab;
 
 extern crate alloc;
 
+pub use large_allocator::LargeAllocator;
+pub use locked_heap::{ByteAllocatorExt, LockedHeap};
+
 const SET_SIZE: usize = 64;
 const MIN_HEAP_SIZE: usize = 0x8000;
 
@@ -16,29 +25,31 @@ use core::ptr::NonNull;
 use core::alloc::Layout;
 use slab::Slab;
 
+/// Backend used for allocations over 8192 bytes (and to refill slabs) when
+/// none is picked explicitly.
+type DefaultLargeAllocator = buddy_system_allocator::Heap<32>;
 
-
-pub struct LabByteAllocator {
-    inner: Option<Heap>,
+pub struct LabByteAllocator<B: LargeAllocator = DefaultLargeAllocator> {
+    inner: Option<Heap<B>>,
 }
 
-impl LabByteAllocator {
+impl<B: LargeAllocator> LabByteAllocator<B> {
     pub const fn new() -> Self {
         Self {
             inner: None,
         }
     }
 
-    fn inner_mut(&mut self) -> &mut Heap {
+    fn inner_mut(&mut self) -> &mut Heap<B> {
         self.inner.as_mut().unwrap()
     }
 
-    fn inner(&self) -> &Heap {
+    fn inner(&self) -> &Heap<B> {
         self.inner.as_ref().unwrap()
     }
 }
 
-impl BaseAllocator for LabByteAllocator {
+impl<B: LargeAllocator> BaseAllocator for LabByteAllocator<B> {
     fn init(&mut self, start: usize, size: usize) {
         self.inner = unsafe { Some(Heap::new(start, size)) };
     }
@@ -50,7 +61,7 @@ impl BaseAllocator for LabByteAllocator {
     }
 }
 
-impl ByteAllocator for LabByteAllocator {
+impl<B: LargeAllocator> ByteAllocator for LabByteAllocator<B> {
     fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
         self.inner_mut()
             .allocate(layout)
@@ -71,6 +82,21 @@ impl ByteAllocator for LabByteAllocator {
     }
 }
 
+impl<B: LargeAllocator> ByteAllocatorExt for LabByteAllocator<B> {
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Option<NonNull<u8>> {
+        self.inner_mut()
+            .realloc(ptr.as_ptr() as usize, old_layout, new_layout)
+            .ok()
+            .map(|addr| unsafe { NonNull::new_unchecked(addr as *mut u8) })
+    }
+}
+
+#[derive(PartialEq, Eq)]
 enum HeapAllocator {
     Slab64Bytes,
     Slab128Bytes,
@@ -80,22 +106,22 @@ enum HeapAllocator {
     Slab2048Bytes,
     Slab4096Bytes,
     Slab8192Bytes,
-    BuddyAllocator,
+    LargeAllocator,
 }
 
-pub struct Heap {
-    slab_64_bytes: Slab<64>,
-    slab_128_bytes: Slab<128>,
-    slab_256_bytes: Slab<256>,
-    slab_512_bytes: Slab<512>,
-    slab_1024_bytes: Slab<1024>,
-    slab_2048_bytes: Slab<2048>,
-    slab_4096_bytes: Slab<4096>,
-    slab_8192_bytes: Slab<8192>,
-    buddy_allocator: buddy_system_allocator::Heap<32>,
+pub struct Heap<B: LargeAllocator = DefaultLargeAllocator> {
+    slab_64_bytes: Slab<64, B>,
+    slab_128_bytes: Slab<128, B>,
+    slab_256_bytes: Slab<256, B>,
+    slab_512_bytes: Slab<512, B>,
+    slab_1024_bytes: Slab<1024, B>,
+    slab_2048_bytes: Slab<2048, B>,
+    slab_4096_bytes: Slab<4096, B>,
+    slab_8192_bytes: Slab<8192, B>,
+    large_allocator: B,
 }
 
-impl Heap {
+impl<B: LargeAllocator> Heap<B> {
     /// Creates a new heap with the given `heap_start_addr` and `heap_size`. The start address must be valid
     /// and the memory in the `[heap_start_addr, heap_start_addr + heap_size)` range must not be used for
     /// anything else.
@@ -103,7 +129,7 @@ impl Heap {
     /// # Safety
     /// This function is unsafe because it can cause undefined behavior if the
     /// given address is invalid.
-    pub unsafe fn new(heap_start_addr: usize, heap_size: usize) -> Heap {
+    pub unsafe fn new(heap_start_addr: usize, heap_size: usize) -> Heap<B> {
         assert!(
             heap_start_addr % 4096 == 0,
             "Start address should be page aligned"
@@ -117,19 +143,15 @@ impl Heap {
             "Heap size should be a multiple of minimum heap size"
         );
         Heap {
-            slab_64_bytes: Slab::<64>::new(0, 0),
-            slab_128_bytes: Slab::<128>::new(0, 0),
-            slab_256_bytes: Slab::<256>::new(0, 0),
-            slab_512_bytes: Slab::<512>::new(0, 0),
-            slab_1024_bytes: Slab::<1024>::new(0, 0),
-            slab_2048_bytes: Slab::<2048>::new(0, 0),
-            slab_4096_bytes: Slab::<4096>::new(0, 0),
-            slab_8192_bytes: Slab::<8192>::new(0, 0),
-            buddy_allocator: {
-                let mut buddy = buddy_system_allocator::Heap::<32>::new();
-                buddy.init(heap_start_addr, heap_size);
-                buddy
-            },
+            slab_64_bytes: Slab::new(0, 0),
+            slab_128_bytes: Slab::new(0, 0),
+            slab_256_bytes: Slab::new(0, 0),
+            slab_512_bytes: Slab::new(0, 0),
+            slab_1024_bytes: Slab::new(0, 0),
+            slab_2048_bytes: Slab::new(0, 0),
+            slab_4096_bytes: Slab::new(0, 0),
+            slab_8192_bytes: Slab::new(0, 0),
+            large_allocator: B::new(heap_start_addr, heap_size),
         }
     }
 
@@ -149,7 +171,7 @@ impl Heap {
             heap_size % 4096 == 0,
             "Add Heap size should be a multiple of page size"
         );
-        self.buddy_allocator
+        self.large_allocator
             .add_to_heap(heap_start_addr, heap_start_addr + heap_size);
     }
 
@@ -171,8 +193,8 @@ impl Heap {
             HeapAllocator::Slab2048Bytes => self.slab_2048_bytes.grow(mem_start_addr, mem_size),
             HeapAllocator::Slab4096Bytes => self.slab_4096_bytes.grow(mem_start_addr, mem_size),
             HeapAllocator::Slab8192Bytes => self.slab_8192_bytes.grow(mem_start_addr, mem_size),
-            HeapAllocator::BuddyAllocator => self
-                .buddy_allocator
+            HeapAllocator::LargeAllocator => self
+                .large_allocator
                 .add_to_heap(mem_start_addr, mem_start_addr + mem_size),
         }
     }
@@ -182,33 +204,33 @@ impl Heap {
     /// This function finds the slab of lowest size which can still accommodate the given chunk.
     /// The runtime is in `O(1)` for chunks of size <= 4096, and `O(n)` when chunk size is > 4096,
     pub fn allocate(&mut self, layout: Layout) -> Result<usize, alloc::alloc::AllocError> {
-        match Heap::layout_to_allocator(&layout) {
+        match Heap::<B>::layout_to_allocator(&layout) {
             HeapAllocator::Slab64Bytes => self
                 .slab_64_bytes
-                .allocate(layout, &mut self.buddy_allocator),
+                .allocate(layout, &mut self.large_allocator),
             HeapAllocator::Slab128Bytes => self
                 .slab_128_bytes
-                .allocate(layout, &mut self.buddy_allocator),
+                .allocate(layout, &mut self.large_allocator),
             HeapAllocator::Slab256Bytes => self
                 .slab_256_bytes
-                .allocate(layout, &mut self.buddy_allocator),
+                .allocate(layout, &mut self.large_allocator),
             HeapAllocator::Slab512Bytes => self
                 .slab_512_bytes
-                .allocate(layout, &mut self.buddy_allocator),
+                .allocate(layout, &mut self.large_allocator),
             HeapAllocator::Slab1024Bytes => self
                 .slab_1024_bytes
-                .allocate(layout, &mut self.buddy_allocator),
+                .allocate(layout, &mut self.large_allocator),
             HeapAllocator::Slab2048Bytes => self
                 .slab_2048_bytes
-                .allocate(layout, &mut self.buddy_allocator),
+                .allocate(layout, &mut self.large_allocator),
             HeapAllocator::Slab4096Bytes => self
                 .slab_4096_bytes
-                .allocate(layout, &mut self.buddy_allocator),
+                .allocate(layout, &mut self.large_allocator),
             HeapAllocator::Slab8192Bytes => self
                 .slab_8192_bytes
-                .allocate(layout, &mut self.buddy_allocator),
-            HeapAllocator::BuddyAllocator => self
-                .buddy_allocator
+                .allocate(layout, &mut self.large_allocator),
+            HeapAllocator::LargeAllocator => self
+                .large_allocator
                 .alloc(layout)
                 .map(|ptr| ptr.as_ptr() as usize)
                 .map_err(|_| alloc::alloc::AllocError),
@@ -227,17 +249,33 @@ impl Heap {
     /// This function is unsafe because it can cause undefined behavior if the
     /// given address is invalid.
     pub unsafe fn deallocate(&mut self, ptr: usize, layout: Layout) {
-        match Heap::layout_to_allocator(&layout) {
-            HeapAllocator::Slab64Bytes => self.slab_64_bytes.deallocate(ptr),
-            HeapAllocator::Slab128Bytes => self.slab_128_bytes.deallocate(ptr),
-            HeapAllocator::Slab256Bytes => self.slab_256_bytes.deallocate(ptr),
-            HeapAllocator::Slab512Bytes => self.slab_512_bytes.deallocate(ptr),
-            HeapAllocator::Slab1024Bytes => self.slab_1024_bytes.deallocate(ptr),
-            HeapAllocator::Slab2048Bytes => self.slab_2048_bytes.deallocate(ptr),
-            HeapAllocator::Slab4096Bytes => self.slab_4096_bytes.deallocate(ptr),
-            HeapAllocator::Slab8192Bytes => self.slab_8192_bytes.deallocate(ptr),
-            HeapAllocator::BuddyAllocator => self
-                .buddy_allocator
+        match Heap::<B>::layout_to_allocator(&layout) {
+            HeapAllocator::Slab64Bytes => self
+                .slab_64_bytes
+                .deallocate(ptr, &mut self.large_allocator),
+            HeapAllocator::Slab128Bytes => self
+                .slab_128_bytes
+                .deallocate(ptr, &mut self.large_allocator),
+            HeapAllocator::Slab256Bytes => self
+                .slab_256_bytes
+                .deallocate(ptr, &mut self.large_allocator),
+            HeapAllocator::Slab512Bytes => self
+                .slab_512_bytes
+                .deallocate(ptr, &mut self.large_allocator),
+            HeapAllocator::Slab1024Bytes => self
+                .slab_1024_bytes
+                .deallocate(ptr, &mut self.large_allocator),
+            HeapAllocator::Slab2048Bytes => self
+                .slab_2048_bytes
+                .deallocate(ptr, &mut self.large_allocator),
+            HeapAllocator::Slab4096Bytes => self
+                .slab_4096_bytes
+                .deallocate(ptr, &mut self.large_allocator),
+            HeapAllocator::Slab8192Bytes => self
+                .slab_8192_bytes
+                .deallocate(ptr, &mut self.large_allocator),
+            HeapAllocator::LargeAllocator => self
+                .large_allocator
                 .dealloc(NonNull::new(ptr as *mut u8).unwrap(), layout),
         }
     }
@@ -245,7 +283,7 @@ impl Heap {
     /// Returns bounds on the guaranteed usable size of a successful
     /// allocation created with the specified `layout`.
     pub fn usable_size(&self, layout: Layout) -> (usize, usize) {
-        match Heap::layout_to_allocator(&layout) {
+        match Heap::<B>::layout_to_allocator(&layout) {
             HeapAllocator::Slab64Bytes => (layout.size(), 64),
             HeapAllocator::Slab128Bytes => (layout.size(), 128),
             HeapAllocator::Slab256Bytes => (layout.size(), 256),
@@ -254,14 +292,54 @@ impl Heap {
             HeapAllocator::Slab2048Bytes => (layout.size(), 2048),
             HeapAllocator::Slab4096Bytes => (layout.size(), 4096),
             HeapAllocator::Slab8192Bytes => (layout.size(), 8192),
-            HeapAllocator::BuddyAllocator => (layout.size(), layout.size()),
+            HeapAllocator::LargeAllocator => (layout.size(), layout.size()),
+        }
+    }
+
+    /// Reallocates `ptr` (previously allocated with `old_layout`) to fit
+    /// `new_layout`. If `new_layout`'s alignment is no stricter than
+    /// `old_layout`'s, and either `old_layout` and `new_layout` land in the
+    /// same slab size class or `new_layout` still fits inside the old
+    /// block's usable size, the existing block already satisfies the
+    /// request and `ptr` is returned unchanged with no data movement.
+    /// Otherwise this falls back to allocate-new + copy + deallocate-old.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by a prior call to `allocate` with
+    /// `old_layout` and not yet deallocated.
+    pub unsafe fn realloc(
+        &mut self,
+        ptr: usize,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<usize, alloc::alloc::AllocError> {
+        let old_class = Heap::<B>::layout_to_allocator(&old_layout);
+        let new_class = Heap::<B>::layout_to_allocator(&new_layout);
+        let (_, old_block_size) = self.usable_size(old_layout);
+
+        let fits_in_place = old_class != HeapAllocator::LargeAllocator
+            && new_class != HeapAllocator::LargeAllocator
+            && new_layout.align() <= old_layout.align()
+            && (old_class == new_class || new_layout.size() <= old_block_size);
+
+        if fits_in_place {
+            return Ok(ptr);
         }
+
+        let new_ptr = self.allocate(new_layout)?;
+        core::ptr::copy_nonoverlapping(
+            ptr as *const u8,
+            new_ptr as *mut u8,
+            old_layout.size().min(new_layout.size()),
+        );
+        self.deallocate(ptr, old_layout);
+        Ok(new_ptr)
     }
 
     /// Finds allocator to use based on layout size and alignment
     fn layout_to_allocator(layout: &Layout) -> HeapAllocator {
         if layout.size() > 8192 {
-            HeapAllocator::BuddyAllocator
+            HeapAllocator::LargeAllocator
         } else if layout.size() <= 64 && layout.align() <= 64 {
             HeapAllocator::Slab64Bytes
         } else if layout.size() <= 128 && layout.align() <= 128 {
@@ -291,7 +369,7 @@ impl Heap {
             + self.slab_2048_bytes.total_blocks() * 2048
             + self.slab_4096_bytes.total_blocks() * 4096
             + self.slab_8192_bytes.total_blocks() * 8192
-            + self.buddy_allocator.stats_total_bytes()
+            + self.large_allocator.total_bytes()
     }
 
     /// Returns allocated memory size in bytes.
@@ -303,11 +381,98 @@ impl Heap {
             + self.slab_1024_bytes.used_blocks() * 1024
             + self.slab_2048_bytes.used_blocks() * 2048
             + self.slab_4096_bytes.used_blocks() * 4096
-            + self.buddy_allocator.stats_alloc_actual()
+            + self.large_allocator.used_bytes()
     }
 
     /// Returns available memory size in bytes.
     pub fn available_bytes(&self) -> usize {
         self.total_bytes() - self.used_bytes()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEAP_SIZE: usize = MIN_HEAP_SIZE;
+
+    fn new_heap() -> (Heap, *mut u8) {
+        let region =
+            unsafe { std::alloc::alloc(Layout::from_size_align(HEAP_SIZE, 4096).unwrap()) };
+        let heap = unsafe { Heap::new(region as usize, HEAP_SIZE) };
+        (heap, region)
+    }
+
+    unsafe fn free_region(region: *mut u8) {
+        unsafe { std::alloc::dealloc(region, Layout::from_size_align(HEAP_SIZE, 4096).unwrap()) };
+    }
+
+    #[test]
+    fn realloc_stays_in_place_within_same_slab_class() {
+        let (mut heap, region) = new_heap();
+        let old_layout = Layout::from_size_align(8, 8).unwrap();
+        let ptr = heap.allocate(old_layout).unwrap();
+
+        let new_layout = Layout::from_size_align(40, 8).unwrap();
+        let new_ptr = unsafe { heap.realloc(ptr, old_layout, new_layout).unwrap() };
+        assert_eq!(new_ptr, ptr, "8 and 40 both land in Slab64Bytes");
+
+        unsafe { free_region(region) };
+    }
+
+    #[test]
+    fn realloc_falls_back_and_copies_across_slab_classes() {
+        let (mut heap, region) = new_heap();
+        let old_layout = Layout::from_size_align(8, 8).unwrap();
+        let ptr = heap.allocate(old_layout).unwrap();
+        unsafe { *(ptr as *mut u8) = 0x42 };
+
+        // 5000 bytes doesn't fit Slab64Bytes's usable size, so this must
+        // move to Slab8192Bytes.
+        let new_layout = Layout::from_size_align(5000, 8).unwrap();
+        let new_ptr = unsafe { heap.realloc(ptr, old_layout, new_layout).unwrap() };
+        assert_ne!(new_ptr, ptr);
+        assert_eq!(unsafe { *(new_ptr as *const u8) }, 0x42);
+
+        unsafe { free_region(region) };
+    }
+
+    #[test]
+    fn realloc_does_not_return_stale_pointer_for_stricter_alignment() {
+        let (mut heap, region) = new_heap();
+        // The first block Slab64Bytes carves out of its backing chunk sits
+        // at an offset that is not 128-byte aligned, so this would fail if
+        // `realloc` ever took the in-place path here.
+        let old_layout = Layout::from_size_align(8, 8).unwrap();
+        let ptr = heap.allocate(old_layout).unwrap();
+
+        let new_layout = Layout::from_size_align(8, 128).unwrap();
+        let new_ptr = unsafe { heap.realloc(ptr, old_layout, new_layout).unwrap() };
+        assert_eq!(new_ptr % 128, 0);
+
+        unsafe { free_region(region) };
+    }
+
+    fn new_heap_with_backend<B: LargeAllocator>() -> (Heap<B>, *mut u8) {
+        let region =
+            unsafe { std::alloc::alloc(Layout::from_size_align(HEAP_SIZE, 4096).unwrap()) };
+        let heap = unsafe { Heap::<B>::new(region as usize, HEAP_SIZE) };
+        (heap, region)
+    }
+
+    /// `Heap<B>` must work end-to-end with any `LargeAllocator`, not just
+    /// the default buddy-based one.
+    #[test]
+    fn large_allocator_backend_is_pluggable() {
+        let (mut heap, region) = new_heap_with_backend::<linked_list_allocator::Heap>();
+
+        let layout = Layout::from_size_align(9000, 8).unwrap();
+        let ptr = heap.allocate(layout).unwrap();
+        assert!(heap.used_bytes() >= 9000);
+
+        unsafe { heap.deallocate(ptr, layout) };
+        assert_eq!(heap.used_bytes(), 0);
+
+        unsafe { free_region(region) };
+    }
+}
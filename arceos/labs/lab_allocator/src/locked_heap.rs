@@ -0,0 +1,155 @@
+//! `GlobalAlloc` wrapper so a `ByteAllocator` can be installed as the
+//! `#[global_allocator]`, which requires `&self` methods instead of the
+//! `&mut self` ones `BaseAllocator`/`ByteAllocator` expose.
+
+use allocator::ByteAllocator;
+use bump_allocator::EarlyAllocator;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ops::Deref;
+use core::ptr::{self, NonNull};
+use spin::Mutex;
+
+/// A `ByteAllocator` that additionally knows how to service `realloc`
+/// without going through the caller.
+///
+/// This mirrors the allocate-new + copy + deallocate-old fallback used by
+/// `GlobalAlloc`'s own default `realloc`; allocators that can do better (see
+/// `Heap::realloc`) override it.
+pub trait ByteAllocatorExt: ByteAllocator {
+    /// # Safety
+    /// `ptr` must have been returned by a prior `alloc(old_layout)` call on
+    /// `self` and not yet freed.
+    unsafe fn realloc(
+        &mut self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Option<NonNull<u8>> {
+        fallback_realloc(self, ptr, old_layout, new_layout)
+    }
+}
+
+/// Shared allocate-new + copy + deallocate-old path for `ByteAllocatorExt`
+/// impls that have no smarter option.
+unsafe fn fallback_realloc<A: ByteAllocator + ?Sized>(
+    allocator: &mut A,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+) -> Option<NonNull<u8>> {
+    let new_ptr = allocator.alloc(new_layout).ok()?;
+    ptr::copy_nonoverlapping(
+        ptr.as_ptr(),
+        new_ptr.as_ptr(),
+        old_layout.size().min(new_layout.size()),
+    );
+    allocator.dealloc(ptr, old_layout);
+    Some(new_ptr)
+}
+
+impl<const PAGE_SIZE: usize> ByteAllocatorExt for EarlyAllocator<PAGE_SIZE> {}
+
+/// Wraps a `ByteAllocator` in a spinlock so it can back a
+/// `#[global_allocator]` static, e.g.:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static HEAP: LockedHeap<LabByteAllocator> = LockedHeap::new(LabByteAllocator::new());
+///
+/// // The wrapped allocator still needs `BaseAllocator::init` called on it
+/// // before first use; `Deref` gives access to the inner `Mutex` for that.
+/// HEAP.lock().init(heap_start, heap_size);
+/// ```
+pub struct LockedHeap<A: ByteAllocatorExt>(Mutex<A>);
+
+impl<A: ByteAllocatorExt> LockedHeap<A> {
+    /// Creates a new `LockedHeap`. The wrapped allocator still needs
+    /// `BaseAllocator::init` called on it before first use, via `lock()`
+    /// (see the `Deref` impl below).
+    pub const fn new(allocator: A) -> Self {
+        Self(Mutex::new(allocator))
+    }
+}
+
+impl<A: ByteAllocatorExt> Deref for LockedHeap<A> {
+    type Target = Mutex<A>;
+
+    fn deref(&self) -> &Mutex<A> {
+        &self.0
+    }
+}
+
+unsafe impl<A: ByteAllocatorExt> GlobalAlloc for LockedHeap<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0
+            .lock()
+            .alloc(layout)
+            .map_or(ptr::null_mut(), |p| p.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            self.0.lock().dealloc(ptr, layout);
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let (Some(ptr), Ok(new_layout)) = (
+            NonNull::new(ptr),
+            Layout::from_size_align(new_size, layout.align()),
+        ) else {
+            return ptr::null_mut();
+        };
+        self.0
+            .lock()
+            .realloc(ptr, layout, new_layout)
+            .map_or(ptr::null_mut(), |p| p.as_ptr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use allocator::BaseAllocator;
+    use std::alloc::{alloc as std_alloc, dealloc as std_dealloc};
+
+    #[test]
+    fn locked_heap_init_via_deref_then_alloc() {
+        let region_layout = Layout::from_size_align(0x8000, 4096).unwrap();
+        let region = unsafe { std_alloc(region_layout) };
+
+        let heap: LockedHeap<EarlyAllocator<4096>> = LockedHeap::new(EarlyAllocator::new());
+        // `Deref` is what makes this possible on a `static` (no `&mut` path
+        // exists once it's behind a `#[global_allocator]`).
+        heap.lock().init(region as usize, region_layout.size());
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let ptr = unsafe { GlobalAlloc::alloc(&heap, layout) };
+        assert!(!ptr.is_null());
+        assert!(ptr as usize >= region as usize);
+        assert!((ptr as usize) < region as usize + region_layout.size());
+
+        unsafe { std_dealloc(region, region_layout) };
+    }
+
+    #[test]
+    fn fallback_realloc_copies_data_into_new_block() {
+        let region_layout = Layout::from_size_align(0x8000, 4096).unwrap();
+        let region = unsafe { std_alloc(region_layout) };
+
+        let mut allocator = EarlyAllocator::<4096>::new();
+        allocator.init(region as usize, region_layout.size());
+
+        let old_layout = Layout::from_size_align(8, 8).unwrap();
+        let old_ptr = ByteAllocator::alloc(&mut allocator, old_layout).unwrap();
+        unsafe { *old_ptr.as_ptr() = 0x42 };
+
+        let new_layout = Layout::from_size_align(64, 8).unwrap();
+        let new_ptr =
+            unsafe { ByteAllocatorExt::realloc(&mut allocator, old_ptr, old_layout, new_layout) }
+                .unwrap();
+        assert_eq!(unsafe { *new_ptr.as_ptr() }, 0x42);
+
+        unsafe { std_dealloc(region, region_layout) };
+    }
+}
@@ -1,9 +1,24 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
 
 use core::ptr::NonNull;
 
 use allocator::{AllocError, BaseAllocator, ByteAllocator, PageAllocator};
 
+/// Number of `u32` words reserved for the page-reclamation bitmap.
+///
+/// Each bit tracks one page slot, so this bounds the number of pages the
+/// early allocator can track for reuse at `32 * PAGE_BITMAP_WORDS` pages.
+/// Regions with more pages than this simply don't get bitmap-backed
+/// reclamation (see `bitmap_capable`); they keep working exactly as before
+/// this feature was added, with `dealloc_pages` a no-op.
+const PAGE_BITMAP_WORDS: usize = 1024;
+const BITS_PER_WORD: usize = u32::BITS as usize;
+const PAGE_BITMAP_BITS: usize = PAGE_BITMAP_WORDS * BITS_PER_WORD;
+
 /// Early memory allocator
 /// Use it before formal bytes-allocator and pages-allocator can work!
 /// This is a double-end memory range:
@@ -16,13 +31,28 @@ use allocator::{AllocError, BaseAllocator, ByteAllocator, PageAllocator};
 ///
 /// For bytes area, 'count' records number of allocations.
 /// When it goes down to ZERO, free bytes-used area.
-/// For pages area, it will never be freed!
 ///
+/// For pages area, freed pages are tracked in `page_bitmap` so that
+/// `alloc_pages` can hand them back out instead of only ever bumping
+/// `p_pos` further down. Slot `i` of the bitmap is the page starting at
+/// `end - (i + 1) * PAGE_SIZE`; bit `i` set means that slot is currently
+/// allocated. Only slots that the bump cursor has already issued at least
+/// once (i.e. `i < (end - p_pos) / PAGE_SIZE`) are ever touched, so the
+/// bitmap-tracked region and the not-yet-issued bump region never overlap.
+///
+/// `page_bitmap` has a fixed capacity (see `PAGE_BITMAP_WORDS`). A region
+/// with more pages than that fits is still usable; it just falls back to
+/// `dealloc_pages` being a no-op and `alloc_pages` always bumping `p_pos`,
+/// i.e. the behavior this allocator had before bitmap-backed reclamation was
+/// added. `bitmap_capable` records which mode `init` chose.
 pub struct EarlyAllocator<const PAGE_SIZE: usize> {
     start: usize,
     end: usize,
     b_pos: usize,
     p_pos: usize,
+    page_bitmap: [u32; PAGE_BITMAP_WORDS],
+    freed_pages: usize,
+    bitmap_capable: bool,
 }
 
 impl<const PAGE_SIZE: usize> EarlyAllocator<PAGE_SIZE> {
@@ -32,17 +62,113 @@ impl<const PAGE_SIZE: usize> EarlyAllocator<PAGE_SIZE> {
             end: 0,
             b_pos: 0,
             p_pos: 0,
+            page_bitmap: [0; PAGE_BITMAP_WORDS],
+            freed_pages: 0,
+            bitmap_capable: false,
+        }
+    }
+
+    /// Number of page slots the bump cursor has issued at least once, i.e.
+    /// the portion of the bitmap that is meaningful to scan.
+    fn issued_slots(&self) -> usize {
+        (self.end - self.p_pos) / PAGE_SIZE
+    }
+
+    /// Address of bitmap slot `slot` (slot 0 sits directly below `end`).
+    fn slot_addr(&self, slot: usize) -> usize {
+        self.end - (slot + 1) * PAGE_SIZE
+    }
+
+    /// Bitmap slot that `addr` (the low end of a page-aligned block) falls in.
+    fn addr_slot(&self, addr: usize) -> usize {
+        (self.end - addr) / PAGE_SIZE - 1
+    }
+
+    fn bitmap_test(&self, slot: usize) -> bool {
+        self.page_bitmap[slot / BITS_PER_WORD] & (1 << (slot % BITS_PER_WORD)) != 0
+    }
+
+    fn bitmap_set(&mut self, slot: usize) {
+        self.page_bitmap[slot / BITS_PER_WORD] |= 1 << (slot % BITS_PER_WORD);
+    }
+
+    fn bitmap_clear(&mut self, slot: usize) {
+        self.page_bitmap[slot / BITS_PER_WORD] &= !(1 << (slot % BITS_PER_WORD));
+    }
+
+    /// Marks the `num_pages` slots backing the freshly bump-issued block
+    /// starting at `addr` as allocated, so they can later be reclaimed.
+    fn mark_issued(&mut self, addr: usize, num_pages: usize) {
+        let last_slot = self.addr_slot(addr);
+        for slot in last_slot + 1 - num_pages..=last_slot {
+            self.bitmap_set(slot);
+        }
+    }
+
+    /// Scans the issued region of the bitmap for a run of `num_pages`
+    /// consecutive free slots. Runs that fit inside a single bitmap word are
+    /// found directly via a shift/mask plus `trailing_zeros`; runs that
+    /// straddle a word boundary fall back to a bit-by-bit scan.
+    fn find_free_run(&self, num_pages: usize) -> Option<usize> {
+        let issued = self.issued_slots();
+        if num_pages == 0 || num_pages > issued {
+            return None;
         }
+
+        let mut slot = 0;
+        while slot + num_pages <= issued {
+            let word_idx = slot / BITS_PER_WORD;
+            let bit_off = slot % BITS_PER_WORD;
+
+            if bit_off + num_pages <= BITS_PER_WORD {
+                let word = self.page_bitmap[word_idx];
+                let mask = if num_pages == BITS_PER_WORD {
+                    u32::MAX
+                } else {
+                    (1u32 << num_pages) - 1
+                };
+                let window = (word >> bit_off) & mask;
+                if window == 0 {
+                    return Some(slot);
+                }
+                slot += (window.trailing_zeros() as usize) + 1;
+            } else {
+                // Candidate run straddles a word boundary; scan bit-by-bit.
+                if self.bitmap_test(slot) {
+                    slot += 1;
+                } else {
+                    let mut run = 1;
+                    while run < num_pages && !self.bitmap_test(slot + run) {
+                        run += 1;
+                    }
+                    if run == num_pages {
+                        return Some(slot);
+                    }
+                    slot += run;
+                }
+            }
+        }
+        None
     }
 }
 
 impl<const PAGE_SIZE: usize> BaseAllocator for EarlyAllocator<PAGE_SIZE> {
     fn init(&mut self, start: usize, size: usize) {
         assert!(PAGE_SIZE.is_power_of_two());
+        // The bitmap slot math (`issued_slots`, `slot_addr`, `addr_slot`,
+        // `mark_issued`) assumes every page sits at `end - k * PAGE_SIZE`
+        // for integer `k`, which only holds if `start`/`size` (and hence
+        // `end`) are page-aligned.
+        assert!(start % PAGE_SIZE == 0 && size % PAGE_SIZE == 0);
         self.start = start;
         self.end = start + size;
         self.b_pos = start;
         self.p_pos = start + size;
+        self.page_bitmap = [0; PAGE_BITMAP_WORDS];
+        self.freed_pages = 0;
+        // A region bigger than the bitmap can track just doesn't get
+        // page reclamation, rather than a hard boot-time panic.
+        self.bitmap_capable = size / PAGE_SIZE <= PAGE_BITMAP_BITS;
     }
 
     fn add_memory(&mut self, start: usize, size: usize) -> allocator::AllocResult {
@@ -87,10 +213,33 @@ impl<const PAGE_SIZE: usize> PageAllocator for EarlyAllocator<PAGE_SIZE> {
     const PAGE_SIZE: usize = PAGE_SIZE;
 
     fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> allocator::AllocResult<usize> {
+        assert!(align_pow2.is_power_of_two());
+        assert!(align_pow2 >= PAGE_SIZE);
+
+        // Bitmap slots are only ever page-aligned, so reclaimed holes can
+        // only satisfy requests that don't need a coarser alignment.
+        if align_pow2 == PAGE_SIZE && self.bitmap_capable {
+            if let Some(slot) = self.find_free_run(num_pages) {
+                for s in slot..slot + num_pages {
+                    self.bitmap_set(s);
+                }
+                self.freed_pages -= num_pages;
+                return Ok(self.slot_addr(slot + num_pages - 1));
+            }
+        }
+
         let size = num_pages * PAGE_SIZE;
-        // TODO alignment
-        let aligned_start = self.p_pos - size;
+        let aligned_start = (self.p_pos - size) & !(align_pow2 - 1);
         if aligned_start >= self.b_pos {
+            // Rounding down to `align_pow2` may leave a gap between
+            // `aligned_start + size` and the old `p_pos`; that gap can't be
+            // handed to anyone else without knowing its own alignment, so it
+            // is marked issued alongside the returned block and counted as
+            // internal waste in `used_pages`.
+            if self.bitmap_capable {
+                let issued_slots = (self.p_pos - aligned_start) / PAGE_SIZE;
+                self.mark_issued(aligned_start, issued_slots);
+            }
             self.p_pos = aligned_start;
             Ok(aligned_start)
         } else {
@@ -99,7 +248,16 @@ impl<const PAGE_SIZE: usize> PageAllocator for EarlyAllocator<PAGE_SIZE> {
     }
 
     fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
-        // do nothing
+        if !self.bitmap_capable {
+            // Same as before bitmap-backed reclamation: the region is too
+            // large to track, so freed pages are simply never reused.
+            return;
+        }
+        let last_slot = self.addr_slot(pos);
+        for slot in last_slot + 1 - num_pages..=last_slot {
+            self.bitmap_clear(slot);
+        }
+        self.freed_pages += num_pages;
     }
 
     fn total_pages(&self) -> usize {
@@ -107,10 +265,88 @@ impl<const PAGE_SIZE: usize> PageAllocator for EarlyAllocator<PAGE_SIZE> {
     }
 
     fn used_pages(&self) -> usize {
-        (self.end - self.p_pos ) / PAGE_SIZE
+        self.issued_slots() - self.freed_pages
     }
 
     fn available_pages(&self) -> usize {
-        (self.p_pos - self.b_pos ) / PAGE_SIZE
+        (self.p_pos - self.b_pos) / PAGE_SIZE + self.freed_pages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: usize = 4096;
+
+    fn new_allocator(num_pages: usize) -> EarlyAllocator<PAGE_SIZE> {
+        let mut a = EarlyAllocator::<PAGE_SIZE>::new();
+        a.init(0x1000_0000, num_pages * PAGE_SIZE);
+        a
+    }
+
+    #[test]
+    fn find_free_run_straddles_word_boundary() {
+        let mut a = new_allocator(40);
+        let addrs: std::vec::Vec<usize> = (0..36)
+            .map(|_| a.alloc_pages(1, PAGE_SIZE).unwrap())
+            .collect();
+
+        // Free slots 28..36 (the last 8 issued), a run that straddles the
+        // BITS_PER_WORD (32) boundary.
+        for &addr in &addrs[28..36] {
+            a.dealloc_pages(addr, 1);
+        }
+        assert_eq!(a.find_free_run(8), Some(28));
+    }
+
+    #[test]
+    fn alloc_pages_reuses_freed_run_across_word_boundary() {
+        let mut a = new_allocator(40);
+        let addrs: std::vec::Vec<usize> = (0..40)
+            .map(|_| a.alloc_pages(1, PAGE_SIZE).unwrap())
+            .collect();
+
+        // Free an 8-page run that straddles the word boundary (slots
+        // 28..36), then confirm a request for exactly that many pages is
+        // satisfied from the hole instead of failing (the bump cursor is
+        // already fully exhausted).
+        for &addr in &addrs[28..36] {
+            a.dealloc_pages(addr, 1);
+        }
+        assert!(a.alloc_pages(8, PAGE_SIZE).is_ok());
+        assert!(a.alloc_pages(1, PAGE_SIZE).is_err());
+    }
+
+    #[test]
+    fn oversized_region_falls_back_to_non_reclaiming_behavior() {
+        let mut a = new_allocator(PAGE_BITMAP_BITS + 1);
+        assert!(!a.bitmap_capable);
+
+        let before = a.available_pages();
+        let addr = a.alloc_pages(1, PAGE_SIZE).unwrap();
+        assert_eq!(a.available_pages(), before - 1);
+
+        a.dealloc_pages(addr, 1);
+        // Unlike a bitmap-capable region, freeing never brings the page
+        // back into `available_pages` — it's just gone, same as before
+        // bitmap-backed reclamation existed.
+        assert_eq!(a.available_pages(), before - 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn init_rejects_non_page_aligned_end() {
+        let mut a = EarlyAllocator::<PAGE_SIZE>::new();
+        // `size` isn't a multiple of PAGE_SIZE, so `end` wouldn't land on
+        // the bitmap's page grid.
+        a.init(0x1000_0000, 3 * PAGE_SIZE + 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn init_rejects_non_page_aligned_start() {
+        let mut a = EarlyAllocator::<PAGE_SIZE>::new();
+        a.init(0x1000_0001, 3 * PAGE_SIZE);
     }
 }